@@ -23,12 +23,28 @@
 //!
 //! This crate provides an abstraction of process-wide memory barrier over different operating
 //! systems and hardware. It is implemented as follows. For recent Linux systems, we use the
-//! `sys_membarrier()` system call; and for those old Linux systems without support for
-//! `sys_membarrier()`, we fall back to the `mprotect()` system call that is known to provide
-//! process-wide memory barrier semantics. For Windows, we use the `FlushProcessWriteBuffers()`
-//! API. For all the other systems, we fall back to the normal `SeqCst` fence for both fast and slow
+//! `sys_membarrier()` system call, preferring the private expedited command (and registering the
+//! process for it) over the global expedited one since it is the cheaper of the two; and for
+//! those old Linux systems without support for `sys_membarrier()`, as well as for macOS and the
+//! BSDs, we fall back to the `mprotect()` system call that is known to provide process-wide
+//! memory barrier semantics there too. For Windows, we use the `FlushProcessWriteBuffers()` API.
+//! For all the other systems, we fall back to the normal `SeqCst` fence for both fast and slow
 //! paths.
 //!
+//! Besides `light()` and `heavy()`, this crate also provides a few special-purpose barriers and
+//! introspection knobs:
+//!
+//! - `strategy()` reports which of the above implementations is actually in use on this machine,
+//!   and `try_init()` lets a caller force a specific one up front instead of leaving it to be
+//!   auto-detected on the first `light()`/`heavy()`.
+//! - `register_thread()` registers the calling thread for private expedited membarrier ahead of
+//!   time, which matters for threads spawned before the strategy has been detected.
+//! - `heavy_sync_core()` is a heavy barrier for JIT / self-modifying code: beyond ordering memory
+//!   accesses, it also makes every other thread execute a core-serializing instruction, so that
+//!   they don't keep running stale cached instructions from a page that was just written to.
+//! - `heavy_cpu()` is a heavy barrier restricted to a single CPU (on Linux, where supported),
+//!   useful for programs that pin their worker threads to cores.
+//!
 //!
 //! # Usage
 //!
@@ -41,6 +57,9 @@
 //! membarrier::light();     // light-weight barrier
 //! membarrier::heavy();     // heavy-weight barrier
 //! fence(Ordering::SeqCst); // normal barrier
+//!
+//! membarrier::strategy();        // which implementation is actually in use
+//! membarrier::heavy_sync_core(); // heavy barrier for JIT / self-modifying code
 //! ```
 //!
 //! # Semantics
@@ -82,33 +101,318 @@ macro_rules! fatal_assert {
     };
 }
 
+/// A choice of process-wide memory barrier implementation.
+///
+/// Returned by `strategy()` to let a caller (e.g. a crossbeam-style epoch GC deciding whether
+/// its fast path can legally use a compiler fence) discover at runtime whether `light()` is
+/// actually cheap on this machine or degrades to a full `SeqCst` fence. Accepted by `try_init()`
+/// to force a specific backend up front.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Barrier {
+    /// The global expedited `membarrier` system call. Linux only.
+    Membarrier,
+    /// The private expedited `membarrier` system call. Linux only.
+    MembarrierPrivate,
+    /// The `mprotect()`-based trick. Linux, macOS, and the BSDs.
+    Mprotect,
+    /// The `FlushProcessWriteBuffers()` API. Windows only.
+    FlushProcessWriteBuffers,
+    /// A plain `SeqCst` fence, used when nothing cheaper is supported.
+    Fallback,
+}
+
 cfg_if! {
-    if #[cfg(all(target_os = "linux"))] {
+    if #[cfg(target_os = "linux")] {
         pub use linux::*;
+        use linux as platform;
     } else if #[cfg(target_os = "windows")] {
         pub use windows::*;
+        use windows as platform;
+    } else if #[cfg(any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly",
+    ))] {
+        pub use bsd::*;
+        use bsd as platform;
     } else {
         pub use default::*;
+        use default as platform;
+    }
+}
+
+mod runtime {
+    use core::sync::atomic::{AtomicU8, Ordering};
+    use {platform, Barrier};
+
+    /// Sentinel meaning "no strategy has been installed yet".
+    const UNINIT: u8 = 0xff;
+
+    fn encode(barrier: Barrier) -> u8 {
+        match barrier {
+            Barrier::Membarrier => 0,
+            Barrier::MembarrierPrivate => 1,
+            Barrier::Mprotect => 2,
+            Barrier::FlushProcessWriteBuffers => 3,
+            Barrier::Fallback => 4,
+        }
+    }
+
+    fn decode(code: u8) -> Barrier {
+        match code {
+            0 => Barrier::Membarrier,
+            1 => Barrier::MembarrierPrivate,
+            2 => Barrier::Mprotect,
+            3 => Barrier::FlushProcessWriteBuffers,
+            4 => Barrier::Fallback,
+            _ => unreachable!(),
+        }
+    }
+
+    static CURRENT: AtomicU8 = AtomicU8::new(UNINIT);
+
+    /// Returns the strategy installed for process-wide memory barriers, auto-detecting (and
+    /// installing) the best one supported by this machine the first time it's called.
+    pub fn strategy() -> Barrier {
+        let code = CURRENT.load(Ordering::SeqCst);
+        if code != UNINIT {
+            return decode(code);
+        }
+
+        let detected = platform::detect();
+        match CURRENT.compare_exchange(UNINIT, encode(detected), Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => detected,
+            // Lost the race to another thread detecting concurrently; use whatever it installed.
+            Err(installed) => decode(installed),
+        }
+    }
+
+    /// Tries to install `preferred` as the strategy for process-wide memory barriers.
+    ///
+    /// Returns `Ok(preferred)` if `preferred` ends up installed, whether by this call or an
+    /// earlier one. Returns `Err(actual)` with whichever strategy is actually installed instead,
+    /// either because `preferred` isn't supported by this machine, or because a strategy other
+    /// than `preferred` was already installed (by an earlier `try_init()`, or by an earlier call
+    /// to `strategy()`, `light()`, or `heavy()` auto-detecting it).
+    pub fn try_init(preferred: Barrier) -> Result<Barrier, Barrier> {
+        if !platform::is_available(preferred) {
+            return Err(strategy());
+        }
+
+        match CURRENT.compare_exchange(UNINIT, encode(preferred), Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => Ok(preferred),
+            Err(installed) => {
+                let installed = decode(installed);
+                if installed == preferred {
+                    Ok(installed)
+                } else {
+                    Err(installed)
+                }
+            }
+        }
+    }
+}
+
+pub use self::runtime::{strategy, try_init};
+
+/// Issues a light memory barrier for fast path.
+///
+/// It issues a compiler fence, which disallows compiler optimizations across itself, unless
+/// `strategy()` resolves to `Barrier::Fallback` on this machine, in which case it issues a
+/// full `SeqCst` fence instead.
+#[inline]
+pub fn light() {
+    platform::light_for(strategy());
+}
+
+/// Issues a heavy memory barrier for slow path.
+///
+/// It uses whichever implementation `strategy()` resolves to on this machine.
+#[inline]
+pub fn heavy() {
+    platform::heavy_for(strategy());
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly",
+))]
+mod mprotect {
+    use core::cell::UnsafeCell;
+    use core::mem;
+    use core::ptr;
+    use core::sync::atomic;
+    use libc;
+
+    struct Barrier {
+        lock: UnsafeCell<libc::pthread_mutex_t>,
+        page: *mut libc::c_void,
+        page_size: libc::size_t,
+    }
+
+    unsafe impl Sync for Barrier {}
+    unsafe impl Send for Barrier {}
+
+    impl Barrier {
+        /// Issues a process-wide barrier by changing access protections of a single mmap-ed
+        /// page. This method is not as fast as the `sys_membarrier()` call, but works very
+        /// similarly.
+        #[inline]
+        fn barrier(&self) {
+            unsafe {
+                // Lock the mutex.
+                fatal_assert!(libc::pthread_mutex_lock(self.lock.get()) == 0);
+
+                // Set the page access protections to read + write.
+                fatal_assert!(
+                    libc::mprotect(
+                        self.page,
+                        self.page_size,
+                        libc::PROT_READ | libc::PROT_WRITE,
+                    ) == 0
+                );
+
+                // Ensure that the page is dirty before we change the protection so that we
+                // prevent the OS from skipping the global TLB flush.
+                let atomic_usize = &*(self.page as *const atomic::AtomicUsize);
+                atomic_usize.fetch_add(1, atomic::Ordering::SeqCst);
+
+                // Set the page access protections to none.
+                //
+                // Changing a page protection from read + write to none causes the OS to issue
+                // an interrupt to flush TLBs on all processors. This also results in flushing
+                // the processor buffers.
+                fatal_assert!(libc::mprotect(self.page, self.page_size, libc::PROT_NONE) == 0);
+
+                // Unlock the mutex.
+                fatal_assert!(libc::pthread_mutex_unlock(self.lock.get()) == 0);
+            }
+        }
+    }
+
+    lazy_static! {
+        /// An alternative solution to `sys_membarrier` that works on older Linux kernels, on
+        /// macOS, and on the BSDs: `mmap`, `mlock`, `mprotect`, and pthread mutexes are available
+        /// on all of them, and toggling a dirty page's protections forces a cross-core TLB flush
+        /// (and, with it, a store-buffer drain) everywhere, not just on Linux.
+        static ref BARRIER: Barrier = {
+            unsafe {
+                // Find out the page size on the current system.
+                let page_size = libc::sysconf(libc::_SC_PAGESIZE);
+                fatal_assert!(page_size > 0);
+                let page_size = page_size as libc::size_t;
+
+                // Create a dummy page.
+                let page = libc::mmap(
+                    ptr::null_mut(),
+                    page_size,
+                    libc::PROT_NONE,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                    -1 as libc::c_int,
+                    0 as libc::off_t,
+                );
+                fatal_assert!(page != libc::MAP_FAILED);
+                fatal_assert!(page as libc::size_t % page_size == 0);
+
+                // Locking the page ensures that it stays in memory during the two mprotect
+                // calls in `Barrier::barrier()`. If the page was unmapped between those calls,
+                // they would not have the expected effect of generating IPI.
+                libc::mlock(page, page_size as libc::size_t);
+
+                // Initialize the mutex.
+                let lock = UnsafeCell::new(libc::PTHREAD_MUTEX_INITIALIZER);
+                let mut attr: libc::pthread_mutexattr_t = mem::uninitialized();
+                fatal_assert!(libc::pthread_mutexattr_init(&mut attr) == 0);
+                fatal_assert!(
+                    libc::pthread_mutexattr_settype(&mut attr, libc::PTHREAD_MUTEX_NORMAL) == 0
+                );
+                fatal_assert!(libc::pthread_mutex_init(lock.get(), &attr) == 0);
+                fatal_assert!(libc::pthread_mutexattr_destroy(&mut attr) == 0);
+
+                Barrier { lock, page, page_size }
+            }
+        };
+    }
+
+    /// Returns `true` if the `mprotect`-based trick is supported.
+    pub fn is_supported() -> bool {
+        if cfg!(target_arch = "x86") || cfg!(target_arch = "x86_64") {
+            true
+        } else if cfg!(target_arch = "aarch64") && cfg!(target_os = "macos") {
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Executes a heavy `mprotect`-based barrier.
+    #[inline]
+    pub fn barrier() {
+        BARRIER.barrier();
     }
 }
 
 #[allow(dead_code)]
 mod default {
     use core::sync::atomic::{fence, Ordering};
+    use Barrier;
+
+    /// Detects the best strategy supported by this machine.
+    ///
+    /// No process-wide barrier is implemented on this platform, so the only option is the
+    /// fallback.
+    pub(crate) fn detect() -> Barrier {
+        Barrier::Fallback
+    }
+
+    /// Returns `true` if `barrier` is supported on this machine.
+    pub(crate) fn is_available(barrier: Barrier) -> bool {
+        match barrier {
+            Barrier::Fallback => true,
+            Barrier::Membarrier
+            | Barrier::MembarrierPrivate
+            | Barrier::Mprotect
+            | Barrier::FlushProcessWriteBuffers => false,
+        }
+    }
 
-    /// Issues a light memory barrier for fast path.
+    /// Issues a light memory barrier for the given resolved strategy.
     ///
     /// It just issues the normal memory barrier instruction.
     #[inline]
-    pub fn light() {
-        fence(Ordering::SeqCst);
+    pub(crate) fn light_for(barrier: Barrier) {
+        match barrier {
+            Barrier::Fallback => fence(Ordering::SeqCst),
+            _ => unreachable!(),
+        }
     }
 
-    /// Issues a heavy memory barrier for slow path.
+    /// Issues a heavy memory barrier for the given resolved strategy.
     ///
     /// It just issues the normal memory barrier instruction.
     #[inline]
-    pub fn heavy() {
+    pub(crate) fn heavy_for(barrier: Barrier) {
+        match barrier {
+            Barrier::Fallback => fence(Ordering::SeqCst),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Issues a heavy memory barrier for slow path, intended for JIT / self-modifying code.
+    ///
+    /// It just issues the normal memory barrier instruction. This platform has no way to make
+    /// other threads execute a core-serializing instruction, so unlike on Linux, this does *not*
+    /// guarantee that other threads' instruction caches see writes to executable pages; do not
+    /// rely on it for that purpose here.
+    #[inline]
+    pub fn heavy_sync_core() {
         fence(Ordering::SeqCst);
     }
 }
@@ -116,29 +420,37 @@ mod default {
 #[cfg(target_os = "linux")]
 mod linux {
     use core::sync::atomic;
+    use mprotect;
+    use Barrier;
 
-    /// A choice between three strategies for process-wide barrier on Linux.
-    #[derive(Clone, Copy, PartialEq, Eq)]
-    enum Strategy {
-        /// Use the `membarrier` system call.
-        Membarrier,
-        /// Use the `mprotect`-based trick.
-        Mprotect,
-        /// Use `SeqCst` fences.
-        Fallback,
+    /// Detects the best strategy supported by this machine, preferring private expedited
+    /// membarrier over global expedited because it is the faster of the two slow paths.
+    ///
+    /// Note that, for both of the `membarrier`-based strategies, detecting support also registers
+    /// the current process for it: the registration must happen before any thread issues
+    /// `heavy()`, so it is performed here, once, rather than on every call.
+    pub(crate) fn detect() -> Barrier {
+        if membarrier::is_private_supported() {
+            Barrier::MembarrierPrivate
+        } else if membarrier::is_supported() {
+            Barrier::Membarrier
+        } else if mprotect::is_supported() {
+            Barrier::Mprotect
+        } else {
+            Barrier::Fallback
+        }
     }
 
-    lazy_static! {
-        /// The right strategy to use on the current machine.
-        static ref STRATEGY: Strategy = {
-            if membarrier::is_supported() {
-                Strategy::Membarrier
-            } else if mprotect::is_supported() {
-                Strategy::Mprotect
-            } else {
-                Strategy::Fallback
-            }
-        };
+    /// Returns `true` if `barrier` is supported on this machine, registering the process for it
+    /// as a side effect if it's one of the `membarrier`-based strategies.
+    pub(crate) fn is_available(barrier: Barrier) -> bool {
+        match barrier {
+            Barrier::MembarrierPrivate => membarrier::is_private_supported(),
+            Barrier::Membarrier => membarrier::is_supported(),
+            Barrier::Mprotect => mprotect::is_supported(),
+            Barrier::Fallback => true,
+            Barrier::FlushProcessWriteBuffers => false,
+        }
     }
 
     mod membarrier {
@@ -162,19 +474,37 @@ mod linux {
             MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED = (1 << 4),
             MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE = (1 << 5),
             MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE = (1 << 6),
+            MEMBARRIER_CMD_PRIVATE_EXPEDITED_RSEQ = (1 << 7),
+            MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_RSEQ = (1 << 8),
         }
 
+        /// Flag that restricts a membarrier command to a single CPU, identified by the `cpu_id`
+        /// argument of `sys_membarrier`, instead of the whole process.
+        const MEMBARRIER_CMD_FLAG_CPU: libc::c_uint = 1 << 0;
+
         /// Call the `sys_membarrier` system call.
+        ///
+        /// `flags` and `cpu_id` are only meaningful together with `MEMBARRIER_CMD_PRIVATE_EXPEDITED_RSEQ`:
+        /// passing `MEMBARRIER_CMD_FLAG_CPU` in `flags` restricts the barrier to `cpu_id` instead
+        /// of every CPU the process is running on. Every other command is process-wide and expects
+        /// `flags` and `cpu_id` to both be zero.
         #[inline]
-        fn sys_membarrier(cmd: membarrier_cmd) -> libc::c_long {
-            unsafe { libc::syscall(libc::SYS_membarrier, cmd as libc::c_int, 0 as libc::c_int) }
+        fn sys_membarrier(cmd: membarrier_cmd, flags: libc::c_uint, cpu_id: libc::c_int) -> libc::c_long {
+            unsafe {
+                libc::syscall(
+                    libc::SYS_membarrier,
+                    cmd as libc::c_int,
+                    flags as libc::c_int,
+                    cpu_id,
+                )
+            }
         }
 
         /// Returns `true` if the `sys_membarrier` call is available.
         pub fn is_supported() -> bool {
-            // Queries which membarrier commands are supported. Checks if private expedited
+            // Queries which membarrier commands are supported. Checks if global expedited
             // membarrier is supported.
-            let ret = sys_membarrier(membarrier_cmd::MEMBARRIER_CMD_QUERY);
+            let ret = sys_membarrier(membarrier_cmd::MEMBARRIER_CMD_QUERY, 0, 0);
             if ret < 0 ||
                 ret & membarrier_cmd::MEMBARRIER_CMD_GLOBAL_EXPEDITED as libc::c_long == 0 ||
                 ret & membarrier_cmd::MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED as libc::c_long == 0
@@ -182,162 +512,219 @@ mod linux {
                 return false;
             }
 
-            // Registers the current process as a user of private expedited membarrier.
-            if sys_membarrier(membarrier_cmd::MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED) < 0 {
+            // Registers the current process as a user of global expedited membarrier.
+            if sys_membarrier(membarrier_cmd::MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED, 0, 0) < 0 {
+                return false;
+            }
+
+            true
+        }
+
+        /// Returns `true` if private expedited `sys_membarrier` is available, registering the
+        /// current process for it as a side effect.
+        pub fn is_private_supported() -> bool {
+            // Queries which membarrier commands are supported. Checks if private expedited
+            // membarrier is supported.
+            let ret = sys_membarrier(membarrier_cmd::MEMBARRIER_CMD_QUERY, 0, 0);
+            if ret < 0 ||
+                ret & membarrier_cmd::MEMBARRIER_CMD_PRIVATE_EXPEDITED as libc::c_long == 0 ||
+                ret & membarrier_cmd::MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED as libc::c_long == 0
+            {
+                return false;
+            }
+
+            // Registers the current process as a user of private expedited membarrier. This must
+            // happen before any thread of the process issues a private expedited barrier, or the
+            // syscall fails with `EPERM`.
+            if sys_membarrier(membarrier_cmd::MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED, 0, 0) < 0 {
                 return false;
             }
 
             true
         }
 
-        /// Executes a heavy `sys_membarrier`-based barrier.
+        /// Executes a heavy global expedited `sys_membarrier`-based barrier.
         #[inline]
         pub fn barrier() {
-            // fatal_assert!(sys_membarrier(membarrier_cmd::MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE) >= 0);
-            fatal_assert!(sys_membarrier(membarrier_cmd::MEMBARRIER_CMD_GLOBAL_EXPEDITED) >= 0);
-        }
-    }
-
-    mod mprotect {
-        use core::cell::UnsafeCell;
-        use core::mem;
-        use core::ptr;
-        use core::sync::atomic;
-        use libc;
-
-        struct Barrier {
-            lock: UnsafeCell<libc::pthread_mutex_t>,
-            page: *mut libc::c_void,
-            page_size: libc::size_t,
-        }
-
-        unsafe impl Sync for Barrier {}
-        unsafe impl Send for Barrier {}
-
-        impl Barrier {
-            /// Issues a process-wide barrier by changing access protections of a single mmap-ed
-            /// page. This method is not as fast as the `sys_membarrier()` call, but works very
-            /// similarly.
-            #[inline]
-            fn barrier(&self) {
-                unsafe {
-                    // Lock the mutex.
-                    fatal_assert!(libc::pthread_mutex_lock(self.lock.get()) == 0);
-
-                    // Set the page access protections to read + write.
-                    fatal_assert!(
-                        libc::mprotect(
-                            self.page,
-                            self.page_size,
-                            libc::PROT_READ | libc::PROT_WRITE,
-                        ) == 0
-                    );
-
-                    // Ensure that the page is dirty before we change the protection so that we
-                    // prevent the OS from skipping the global TLB flush.
-                    let atomic_usize = &*(self.page as *const atomic::AtomicUsize);
-                    atomic_usize.fetch_add(1, atomic::Ordering::SeqCst);
-
-                    // Set the page access protections to none.
-                    //
-                    // Changing a page protection from read + write to none causes the OS to issue
-                    // an interrupt to flush TLBs on all processors. This also results in flushing
-                    // the processor buffers.
-                    fatal_assert!(libc::mprotect(self.page, self.page_size, libc::PROT_NONE) == 0);
-
-                    // Unlock the mutex.
-                    fatal_assert!(libc::pthread_mutex_unlock(self.lock.get()) == 0);
-                }
+            fatal_assert!(sys_membarrier(membarrier_cmd::MEMBARRIER_CMD_GLOBAL_EXPEDITED, 0, 0) >= 0);
+        }
+
+        /// Executes a heavy private expedited `sys_membarrier`-based barrier.
+        ///
+        /// The calling thread must already be registered for private expedited membarrier, either
+        /// because the installed strategy was detected as `MembarrierPrivate`, or through an
+        /// explicit call to `register_thread()`. Otherwise, the syscall fails with `EPERM`, which
+        /// trips the `fatal_assert!` below.
+        #[inline]
+        pub fn barrier_private() {
+            fatal_assert!(sys_membarrier(membarrier_cmd::MEMBARRIER_CMD_PRIVATE_EXPEDITED, 0, 0) >= 0);
+        }
+
+        /// Returns `true` if private expedited SYNC_CORE `sys_membarrier` is available,
+        /// registering the current process for it as a side effect.
+        pub fn is_sync_core_supported() -> bool {
+            let ret = sys_membarrier(membarrier_cmd::MEMBARRIER_CMD_QUERY, 0, 0);
+            if ret < 0 ||
+                ret & membarrier_cmd::MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE as libc::c_long == 0 ||
+                ret & membarrier_cmd::MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE as libc::c_long == 0
+            {
+                return false;
+            }
+
+            // Registers the current process as a user of private expedited SYNC_CORE membarrier.
+            if sys_membarrier(membarrier_cmd::MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE, 0, 0) < 0 {
+                return false;
             }
+
+            true
         }
 
-        lazy_static! {
-            /// An alternative solution to `sys_membarrier` that works on older Linux kernels and
-            /// x86/x86-64 systems.
-            static ref BARRIER: Barrier = {
-                unsafe {
-                    // Find out the page size on the current system.
-                    let page_size = libc::sysconf(libc::_SC_PAGESIZE);
-                    fatal_assert!(page_size > 0);
-                    let page_size = page_size as libc::size_t;
-
-                    // Create a dummy page.
-                    let page = libc::mmap(
-                        ptr::null_mut(),
-                        page_size,
-                        libc::PROT_NONE,
-                        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
-                        -1 as libc::c_int,
-                        0 as libc::off_t,
-                    );
-                    fatal_assert!(page != libc::MAP_FAILED);
-                    fatal_assert!(page as libc::size_t % page_size == 0);
-
-                    // Locking the page ensures that it stays in memory during the two mprotect
-                    // calls in `Barrier::barrier()`. If the page was unmapped between those calls,
-                    // they would not have the expected effect of generating IPI.
-                    libc::mlock(page, page_size as libc::size_t);
-
-                    // Initialize the mutex.
-                    let lock = UnsafeCell::new(libc::PTHREAD_MUTEX_INITIALIZER);
-                    let mut attr: libc::pthread_mutexattr_t = mem::uninitialized();
-                    fatal_assert!(libc::pthread_mutexattr_init(&mut attr) == 0);
-                    fatal_assert!(
-                        libc::pthread_mutexattr_settype(&mut attr, libc::PTHREAD_MUTEX_NORMAL) == 0
-                    );
-                    fatal_assert!(libc::pthread_mutex_init(lock.get(), &attr) == 0);
-                    fatal_assert!(libc::pthread_mutexattr_destroy(&mut attr) == 0);
-
-                    Barrier { lock, page, page_size }
-                }
-            };
+        /// Executes a heavy private expedited SYNC_CORE `sys_membarrier`-based barrier.
+        ///
+        /// Beyond ordering memory accesses, this guarantees that every targeted thread executes a
+        /// core-serializing instruction before returning, flushing its instruction pipeline. The
+        /// calling thread must already be registered via `is_sync_core_supported`.
+        #[inline]
+        pub fn barrier_sync_core() {
+            fatal_assert!(
+                sys_membarrier(membarrier_cmd::MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE, 0, 0) >= 0
+            );
         }
 
-        /// Returns `true` if the `mprotect`-based trick is supported.
-        pub fn is_supported() -> bool {
-            if cfg!(target_arch = "x86") || cfg!(target_arch = "x86_64") {
-                true
-            } else {
-                false
+        /// Returns `true` if private expedited RSEQ `sys_membarrier` is available, registering
+        /// the current process for it as a side effect.
+        pub fn is_rseq_supported() -> bool {
+            let ret = sys_membarrier(membarrier_cmd::MEMBARRIER_CMD_QUERY, 0, 0);
+            if ret < 0 ||
+                ret & membarrier_cmd::MEMBARRIER_CMD_PRIVATE_EXPEDITED_RSEQ as libc::c_long == 0 ||
+                ret & membarrier_cmd::MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_RSEQ as libc::c_long == 0
+            {
+                return false;
+            }
+
+            // Registers the current process as a user of private expedited RSEQ membarrier.
+            if sys_membarrier(membarrier_cmd::MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_RSEQ, 0, 0) < 0 {
+                return false;
             }
+
+            true
         }
 
-        /// Executes a heavy `mprotect`-based barrier.
+        /// Executes a heavy private expedited RSEQ `sys_membarrier`-based barrier restricted to a
+        /// single CPU, rather than every CPU the process is running on.
         #[inline]
-        pub fn barrier() {
-            BARRIER.barrier();
+        pub fn barrier_cpu(cpu_id: u32) {
+            fatal_assert!(
+                sys_membarrier(
+                    membarrier_cmd::MEMBARRIER_CMD_PRIVATE_EXPEDITED_RSEQ,
+                    MEMBARRIER_CMD_FLAG_CPU,
+                    cpu_id as libc::c_int,
+                ) >= 0
+            );
         }
     }
 
-    /// Issues a light memory barrier for fast path.
+    /// Issues a light memory barrier for the given resolved strategy.
     ///
     /// It issues a compiler fence, which disallows compiler optimizations across itself. It incurs
-    /// basically no costs in run-time.
+    /// basically no costs in run-time. For `Barrier::Fallback`, it issues a full `SeqCst` fence
+    /// instead.
     #[inline]
-    #[allow(dead_code)]
-    pub fn light() {
-        use self::Strategy::*;
-        match *STRATEGY {
-            Membarrier | Mprotect => atomic::compiler_fence(atomic::Ordering::SeqCst),
-            Fallback => atomic::fence(atomic::Ordering::SeqCst),
+    pub(crate) fn light_for(barrier: Barrier) {
+        match barrier {
+            Barrier::MembarrierPrivate | Barrier::Membarrier | Barrier::Mprotect => {
+                atomic::compiler_fence(atomic::Ordering::SeqCst)
+            }
+            Barrier::Fallback => atomic::fence(atomic::Ordering::SeqCst),
+            Barrier::FlushProcessWriteBuffers => unreachable!(),
         }
     }
 
-    /// Issues a heavy memory barrier for slow path.
+    /// Issues a heavy memory barrier for the given resolved strategy.
     ///
-    /// It issues a private expedited membarrier using the `sys_membarrier()` system call, if
-    /// supported; otherwise, it falls back to `mprotect()`-based process-wide memory barrier.
+    /// It prefers a private expedited membarrier using the `sys_membarrier()` system call, since
+    /// it is the cheapest of the process-wide barriers; if unsupported, it falls back to global
+    /// expedited membarrier, then to the `mprotect()`-based process-wide memory barrier, and
+    /// finally to a plain `SeqCst` fence.
     #[inline]
-    #[allow(dead_code)]
-    pub fn heavy() {
-        use self::Strategy::*;
-        match *STRATEGY {
-            Membarrier => membarrier::barrier(),
-            Mprotect => mprotect::barrier(),
-            Fallback => atomic::fence(atomic::Ordering::SeqCst),
+    pub(crate) fn heavy_for(barrier: Barrier) {
+        match barrier {
+            Barrier::MembarrierPrivate => membarrier::barrier_private(),
+            Barrier::Membarrier => membarrier::barrier(),
+            Barrier::Mprotect => mprotect::barrier(),
+            Barrier::Fallback => atomic::fence(atomic::Ordering::SeqCst),
+            Barrier::FlushProcessWriteBuffers => unreachable!(),
         }
     }
 
+    /// Registers the calling thread for private expedited membarrier.
+    ///
+    /// Private expedited membarrier requires every participating thread to be registered, either
+    /// directly or by inheriting the process-wide registration that detecting the strategy
+    /// performs the first time `strategy()`, `light()`, or `heavy()` is called.
+    /// Issuing a private expedited barrier without that registration makes the underlying syscall
+    /// fail with `EPERM`, tripping the `fatal_assert!` in `membarrier::barrier_private`; `heavy()`
+    /// itself always detects the strategy before issuing the barrier.
+    ///
+    /// Call this function once, before spawning threads, to force the strategy (and therefore
+    /// registration) to be detected up front.
+    #[inline]
+    pub fn register_thread() {
+        let _ = super::strategy();
+    }
+
+    lazy_static! {
+        /// Whether private expedited SYNC_CORE membarrier is supported (and, as a side effect of
+        /// checking, registered) on the current machine.
+        static ref SYNC_CORE_SUPPORTED: bool = membarrier::is_sync_core_supported();
+    }
+
+    /// Returns `true` if `heavy_sync_core()` issues an actual core-serializing barrier on this
+    /// machine, rather than falling back to a plain `SeqCst` fence.
+    #[inline]
+    pub fn sync_core_supported() -> bool {
+        *SYNC_CORE_SUPPORTED
+    }
+
+    /// Issues a heavy memory barrier for slow path, additionally guaranteeing that every targeted
+    /// thread executes a core-serializing instruction before returning.
+    ///
+    /// This is what a JIT or other self-modifying-code runtime needs after writing new
+    /// instructions, so that other cores don't keep executing stale cached instructions. Falls
+    /// back to a plain `SeqCst` fence, which does *not* provide that guarantee, when
+    /// `sync_core_supported()` is `false`.
+    #[inline]
+    pub fn heavy_sync_core() {
+        if *SYNC_CORE_SUPPORTED {
+            membarrier::barrier_sync_core();
+        } else {
+            atomic::fence(atomic::Ordering::SeqCst);
+        }
+    }
+
+    lazy_static! {
+        /// Whether private expedited RSEQ membarrier, which allows targeting a single CPU, is
+        /// supported (and, as a side effect of checking, registered) on the current machine.
+        static ref RSEQ_SUPPORTED: bool = membarrier::is_rseq_supported();
+    }
+
+    /// Returns `true` if `heavy_cpu()` is available on this machine.
+    #[inline]
+    pub fn cpu_supported() -> bool {
+        *RSEQ_SUPPORTED
+    }
+
+    /// Issues a heavy memory barrier for slow path, targeting only `cpu_id` rather than every CPU
+    /// the process is running on.
+    ///
+    /// This lets a program that pins its worker threads to cores shoot down just the one CPU
+    /// instead of the whole process. Only call this when `cpu_supported()` is `true`.
+    #[inline]
+    pub fn heavy_cpu(cpu_id: u32) {
+        fatal_assert!(*RSEQ_SUPPORTED);
+        membarrier::barrier_cpu(cpu_id);
+    }
+
     /// Issues a light memory barrier for fast path using membarrier.
     ///
     /// It issues a compiler fence, which disallows compiler optimizations across itself. It incurs
@@ -369,26 +756,136 @@ mod linux {
     }
 }
 
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly",
+))]
+mod bsd {
+    use core::sync::atomic;
+    use mprotect;
+    use Barrier;
+
+    /// Detects the best strategy supported by this machine.
+    pub(crate) fn detect() -> Barrier {
+        if mprotect::is_supported() {
+            Barrier::Mprotect
+        } else {
+            Barrier::Fallback
+        }
+    }
+
+    /// Returns `true` if `barrier` is supported on this machine.
+    pub(crate) fn is_available(barrier: Barrier) -> bool {
+        match barrier {
+            Barrier::Mprotect => mprotect::is_supported(),
+            Barrier::Fallback => true,
+            Barrier::Membarrier | Barrier::MembarrierPrivate | Barrier::FlushProcessWriteBuffers => false,
+        }
+    }
+
+    /// Issues a light memory barrier for the given resolved strategy.
+    ///
+    /// It issues a compiler fence, which disallows compiler optimizations across itself. It incurs
+    /// basically no costs in run-time. For `Barrier::Fallback`, it issues a full `SeqCst` fence
+    /// instead.
+    #[inline]
+    pub(crate) fn light_for(barrier: Barrier) {
+        match barrier {
+            Barrier::Mprotect => atomic::compiler_fence(atomic::Ordering::SeqCst),
+            Barrier::Fallback => atomic::fence(atomic::Ordering::SeqCst),
+            Barrier::Membarrier | Barrier::MembarrierPrivate | Barrier::FlushProcessWriteBuffers => {
+                unreachable!()
+            }
+        }
+    }
+
+    /// Issues a heavy memory barrier for the given resolved strategy.
+    ///
+    /// It issues an `mprotect()`-based process-wide memory barrier, if supported; otherwise, it
+    /// falls back to a normal `SeqCst` fence.
+    #[inline]
+    pub(crate) fn heavy_for(barrier: Barrier) {
+        match barrier {
+            Barrier::Mprotect => mprotect::barrier(),
+            Barrier::Fallback => atomic::fence(atomic::Ordering::SeqCst),
+            Barrier::Membarrier | Barrier::MembarrierPrivate | Barrier::FlushProcessWriteBuffers => {
+                unreachable!()
+            }
+        }
+    }
+
+    /// Issues a heavy memory barrier for slow path, intended for JIT / self-modifying code.
+    ///
+    /// Neither the `mprotect()`-based trick nor a plain `SeqCst` fence makes other threads execute
+    /// a core-serializing instruction, so this falls back to a `SeqCst` fence and does *not*
+    /// guarantee that other threads' instruction caches see writes to executable pages; do not
+    /// rely on it for that purpose here.
+    #[inline]
+    pub fn heavy_sync_core() {
+        atomic::fence(atomic::Ordering::SeqCst);
+    }
+}
+
 #[cfg(target_os = "windows")]
 mod windows {
     use core::sync::atomic;
     use kernel32;
+    use Barrier;
 
-    /// Issues light memory barrier for fast path.
+    /// Detects the best strategy supported by this machine.
+    ///
+    /// `FlushProcessWriteBuffers()` is always available on Windows, so there is nothing to
+    /// auto-detect.
+    pub(crate) fn detect() -> Barrier {
+        Barrier::FlushProcessWriteBuffers
+    }
+
+    /// Returns `true` if `barrier` is supported on this machine.
+    pub(crate) fn is_available(barrier: Barrier) -> bool {
+        match barrier {
+            Barrier::FlushProcessWriteBuffers | Barrier::Fallback => true,
+            Barrier::Membarrier | Barrier::MembarrierPrivate | Barrier::Mprotect => false,
+        }
+    }
+
+    /// Issues a light memory barrier for the given resolved strategy.
     ///
     /// It issues compiler fence, which disallows compiler optimizations across itself.
+    /// For `Barrier::Fallback`, it issues a full `SeqCst` fence instead.
     #[inline]
-    pub fn light() {
-        atomic::compiler_fence(atomic::Ordering::SeqCst);
+    pub(crate) fn light_for(barrier: Barrier) {
+        match barrier {
+            Barrier::FlushProcessWriteBuffers => atomic::compiler_fence(atomic::Ordering::SeqCst),
+            Barrier::Fallback => atomic::fence(atomic::Ordering::SeqCst),
+            Barrier::Membarrier | Barrier::MembarrierPrivate | Barrier::Mprotect => unreachable!(),
+        }
     }
 
-    /// Issues heavy memory barrier for slow path.
+    /// Issues a heavy memory barrier for the given resolved strategy.
     ///
     /// It invokes the `FlushProcessWriteBuffers()` system call.
+    /// For `Barrier::Fallback`, it issues a normal `SeqCst` fence instead.
     #[inline]
-    pub fn heavy() {
-        unsafe {
-            kernel32::FlushProcessWriteBuffers();
+    pub(crate) fn heavy_for(barrier: Barrier) {
+        match barrier {
+            Barrier::FlushProcessWriteBuffers => unsafe {
+                kernel32::FlushProcessWriteBuffers();
+            },
+            Barrier::Fallback => atomic::fence(atomic::Ordering::SeqCst),
+            Barrier::Membarrier | Barrier::MembarrierPrivate | Barrier::Mprotect => unreachable!(),
         }
     }
+
+    /// Issues a heavy memory barrier for slow path, intended for JIT / self-modifying code.
+    ///
+    /// Windows has no equivalent of Linux's SYNC_CORE membarrier, so this falls back to a plain
+    /// `SeqCst` fence and does *not* guarantee that other threads' instruction caches see writes
+    /// to executable pages; do not rely on it for that purpose here.
+    #[inline]
+    pub fn heavy_sync_core() {
+        atomic::fence(atomic::Ordering::SeqCst);
+    }
 }
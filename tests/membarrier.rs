@@ -3,10 +3,39 @@
 extern crate membarrier;
 
 use core::sync::atomic::{fence, Ordering};
+use membarrier::Barrier;
 
 #[test]
 fn fences() {
+    // Forces the strategy (and, on Linux, this thread's registration for private expedited
+    // membarrier) to be detected up front, rather than lazily on the first `light()`/`heavy()`.
+    #[cfg(target_os = "linux")]
+    {
+        membarrier::register_thread();
+
+        if membarrier::cpu_supported() {
+            membarrier::heavy_cpu(0);
+        }
+    }
+
     membarrier::light();     // light-weight barrier
     fence(Ordering::SeqCst); // normal barrier
     membarrier::heavy();     // heavy-weight barrier
+    membarrier::heavy_sync_core(); // heavy barrier for JIT / self-modifying code
+
+    // The calls above have already triggered auto-detection, so a strategy is definitely
+    // installed by now: requesting it again hits `try_init()`'s "already installed, and it
+    // matches" path.
+    let installed = membarrier::strategy();
+    assert_eq!(membarrier::try_init(installed), Ok(installed));
+
+    // Requesting a different strategy is refused, either because it isn't supported here or
+    // because something else is already installed: it reports the active strategy back instead
+    // of silently overriding it.
+    let other = if installed == Barrier::Fallback {
+        Barrier::Mprotect
+    } else {
+        Barrier::Fallback
+    };
+    assert_eq!(membarrier::try_init(other), Err(installed));
 }